@@ -1,26 +1,66 @@
-use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+mod transport;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use serde::Serialize;
 use std::collections::HashMap;
-use std::io::{Read, Write};
+use std::io::Read;
 use std::sync::Mutex;
 use std::thread;
 use tauri::{AppHandle, Emitter};
+use transport::{LocalTransport, PtyTransport, SshTransport, TransportSpec};
 
 // Store active PTY sessions
 lazy_static::lazy_static! {
     static ref PTY_SESSIONS: Mutex<HashMap<String, PtySession>> = Mutex::new(HashMap::new());
 }
 
+// Default cap on the per-session scrollback buffer, in bytes.
+const DEFAULT_SCROLLBACK_CAP: usize = 256 * 1024;
+
+// Cap on the per-session LSP framing buffer, in bytes. Backstops sessions
+// where output never produces a parseable header block (e.g. plain text
+// with no blank line at all), so the buffer can't grow unbounded even while
+// LSP decoding is enabled.
+const LSP_BUFFER_CAP: usize = 1024 * 1024;
+
+#[derive(Clone, Copy, PartialEq)]
+enum PtyEncoding {
+    Utf8,
+    Base64,
+}
+
+impl PtyEncoding {
+    fn from_option(encoding: Option<&str>) -> Self {
+        match encoding {
+            Some("base64") => PtyEncoding::Base64,
+            _ => PtyEncoding::Utf8,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            PtyEncoding::Utf8 => "utf8",
+            PtyEncoding::Base64 => "base64",
+        }
+    }
+}
+
 struct PtySession {
-    writer: Box<dyn Write + Send>,
-    child: Box<dyn portable_pty::Child + Send>,
-    master: Box<dyn portable_pty::MasterPty + Send>,
+    transport: Box<dyn PtyTransport>,
+    scrollback: Vec<u8>,
+    scrollback_cap: usize,
+    emitting: bool,
+    encoding: PtyEncoding,
+    lsp_enabled: bool,
+    lsp_buffer: Vec<u8>,
+    alive: bool,
 }
 
 #[derive(Clone, Serialize)]
 struct PtyData {
     session_id: String,
     data: String,
+    encoding: &'static str,
 }
 
 #[derive(Clone, Serialize)]
@@ -29,34 +69,106 @@ struct PtyExit {
     code: i32,
 }
 
+#[derive(Clone, Serialize)]
+pub struct PtyAttachResult {
+    scrollback: String,
+    alive: bool,
+}
+
+#[derive(Clone, Serialize)]
+struct PtyLsp {
+    session_id: String,
+    json: String,
+}
+
+/// Pulls one complete `Content-Length`-framed LSP message out of `buffer`, if
+/// one is available, draining the consumed bytes. Handles messages split
+/// across reads and multiple messages queued up in a single read. Ordinary
+/// terminal output routinely contains a `\r\n\r\n` that isn't a real LSP
+/// header (blank lines, binary bytes); when that happens the block up to and
+/// including it is dropped so the buffer can't grow without bound, and
+/// scanning continues for a real header further along.
+fn take_lsp_message(buffer: &mut Vec<u8>) -> Option<String> {
+    loop {
+        let header_end = buffer.windows(4).position(|window| window == b"\r\n\r\n")? + 4;
+
+        let content_length = std::str::from_utf8(&buffer[..header_end])
+            .ok()
+            .and_then(|header| {
+                header
+                    .lines()
+                    .find_map(|line| line.strip_prefix("Content-Length:"))
+            })
+            .and_then(|value| value.trim().parse::<usize>().ok());
+
+        let content_length = match content_length {
+            Some(content_length) => content_length,
+            None => {
+                buffer.drain(..header_end);
+                continue;
+            }
+        };
+
+        if buffer.len() < header_end + content_length {
+            return None;
+        }
+
+        let body = buffer[header_end..header_end + content_length].to_vec();
+        buffer.drain(..header_end + content_length);
+        return String::from_utf8(body).ok();
+    }
+}
+
 #[tauri::command]
 pub async fn spawn_pty(
     app: AppHandle,
     session_id: String,
     cwd: String,
+    command: Option<String>,
+    args: Option<Vec<String>>,
+    env: Option<HashMap<String, String>>,
+    cols: Option<u16>,
+    rows: Option<u16>,
+    scrollback_cap: Option<usize>,
+    encoding: Option<String>,
+    transport: Option<TransportSpec>,
+    lsp: Option<bool>,
 ) -> Result<(), String> {
-    let pty_system = native_pty_system();
-
-    let pair = pty_system
-        .openpty(PtySize {
-            rows: 24,
-            cols: 80,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
-        .map_err(|e| e.to_string())?;
-
-    // Spawn the claude CLI
-    let mut cmd = CommandBuilder::new("claude");
-    cmd.cwd(&cwd);
-
-    let child = pair.slave.spawn_command(cmd).map_err(|e| e.to_string())?;
+    let lsp_enabled = lsp.unwrap_or(false);
+    let encoding = PtyEncoding::from_option(encoding.as_deref());
+    let command = command.unwrap_or_else(|| "claude".to_string());
+    let args = args.unwrap_or_default();
+    let env = env.unwrap_or_default();
+    let cols = cols.unwrap_or(80);
+    let rows = rows.unwrap_or(24);
 
-    // Get writer for input
-    let writer = pair.master.take_writer().map_err(|e| e.to_string())?;
+    let transport: Box<dyn PtyTransport> = match transport.unwrap_or_default() {
+        TransportSpec::Local => Box::new(LocalTransport::spawn(
+            &cwd, command, args, env, cols, rows,
+        )?),
+        TransportSpec::Ssh {
+            host,
+            port,
+            username,
+            password,
+            private_key_path,
+        } => Box::new(SshTransport::connect(
+            &host,
+            port,
+            &username,
+            password.as_deref(),
+            private_key_path.as_deref(),
+            &cwd,
+            command,
+            args,
+            env,
+            cols,
+            rows,
+        )?),
+    };
 
     // Get reader for output
-    let mut reader = pair.master.try_clone_reader().map_err(|e| e.to_string())?;
+    let mut reader = transport.try_clone_reader()?;
 
     // Store the session
     {
@@ -64,9 +176,14 @@ pub async fn spawn_pty(
         sessions.insert(
             session_id.clone(),
             PtySession {
-                writer,
-                child,
-                master: pair.master,
+                transport,
+                scrollback: Vec::new(),
+                scrollback_cap: scrollback_cap.unwrap_or(DEFAULT_SCROLLBACK_CAP),
+                emitting: true,
+                encoding,
+                lsp_enabled,
+                lsp_buffer: Vec::new(),
+                alive: true,
             },
         );
     }
@@ -76,32 +193,137 @@ pub async fn spawn_pty(
     let session_id_clone = session_id.clone();
     thread::spawn(move || {
         let mut buf = [0u8; 4096];
+        // Incomplete UTF-8 tail carried over from the previous read, so multi-byte
+        // characters split across the 4096-byte boundary aren't mangled into U+FFFD.
+        let mut leftover: Vec<u8> = Vec::new();
         loop {
             match reader.read(&mut buf) {
                 Ok(0) => break,
                 Ok(n) => {
-                    let data = String::from_utf8_lossy(&buf[..n]).to_string();
-                    let _ = app_clone.emit(
-                        "pty-data",
-                        PtyData {
-                            session_id: session_id_clone.clone(),
-                            data,
-                        },
-                    );
+                    let (should_emit, lsp_messages) = {
+                        let mut sessions = PTY_SESSIONS.lock().unwrap();
+                        if let Some(session) = sessions.get_mut(&session_id_clone) {
+                            session.scrollback.extend_from_slice(&buf[..n]);
+                            if session.scrollback.len() > session.scrollback_cap {
+                                let overflow = session.scrollback.len() - session.scrollback_cap;
+                                session.scrollback.drain(..overflow);
+                            }
+
+                            let mut messages = Vec::new();
+                            if session.lsp_enabled {
+                                session.lsp_buffer.extend_from_slice(&buf[..n]);
+                                while let Some(json) = take_lsp_message(&mut session.lsp_buffer) {
+                                    messages.push(json);
+                                }
+                                if session.lsp_buffer.len() > LSP_BUFFER_CAP {
+                                    let overflow = session.lsp_buffer.len() - LSP_BUFFER_CAP;
+                                    session.lsp_buffer.drain(..overflow);
+                                }
+                            }
+
+                            (session.emitting, messages)
+                        } else {
+                            (false, Vec::new())
+                        }
+                    };
+
+                    if should_emit {
+                        for json in lsp_messages {
+                            let _ = app_clone.emit(
+                                "pty-lsp",
+                                PtyLsp {
+                                    session_id: session_id_clone.clone(),
+                                    json,
+                                },
+                            );
+                        }
+                    }
+
+                    let data = if encoding == PtyEncoding::Base64 {
+                        BASE64.encode(&buf[..n])
+                    } else {
+                        leftover.extend_from_slice(&buf[..n]);
+                        let mut out = String::new();
+                        loop {
+                            match std::str::from_utf8(&leftover) {
+                                Ok(s) => {
+                                    out.push_str(s);
+                                    leftover.clear();
+                                    break;
+                                }
+                                Err(e) => {
+                                    let valid_up_to = e.valid_up_to();
+                                    out.push_str(
+                                        std::str::from_utf8(&leftover[..valid_up_to]).unwrap(),
+                                    );
+                                    match e.error_len() {
+                                        // Genuinely invalid byte(s), not just a truncated
+                                        // tail: emit a replacement char and skip past them
+                                        // so the stream can't wedge on bad input.
+                                        Some(bad_len) => {
+                                            out.push('\u{FFFD}');
+                                            leftover.drain(..valid_up_to + bad_len);
+                                        }
+                                        // Incomplete sequence at the end of this read;
+                                        // keep it for the next read and stop.
+                                        None => {
+                                            leftover.drain(..valid_up_to);
+                                            break;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        out
+                    };
+
+                    if should_emit {
+                        let _ = app_clone.emit(
+                            "pty-data",
+                            PtyData {
+                                session_id: session_id_clone.clone(),
+                                data,
+                                encoding: encoding.as_str(),
+                            },
+                        );
+                    }
                 }
                 Err(_) => break,
             }
         }
 
-        // Process exited, get exit code
+        // Flush any trailing incomplete UTF-8 bytes now that the stream is done.
+        if !leftover.is_empty() {
+            let still_emitting = {
+                let sessions = PTY_SESSIONS.lock().unwrap();
+                sessions
+                    .get(&session_id_clone)
+                    .map(|s| s.emitting)
+                    .unwrap_or(false)
+            };
+            if still_emitting {
+                let data = String::from_utf8_lossy(&leftover).to_string();
+                let _ = app_clone.emit(
+                    "pty-data",
+                    PtyData {
+                        session_id: session_id_clone.clone(),
+                        data,
+                        encoding: PtyEncoding::Utf8.as_str(),
+                    },
+                );
+            }
+        }
+
+        // Process exited, get exit code. The session (and its scrollback) is
+        // kept around rather than removed, so a later attach_pty can still
+        // replay history for a client that reconnects after the child died;
+        // kill_pty remains the explicit way to tear a session down.
         let exit_code = {
             let mut sessions = PTY_SESSIONS.lock().unwrap();
-            if let Some(mut session) = sessions.remove(&session_id_clone) {
-                session
-                    .child
-                    .wait()
-                    .map(|status| status.exit_code() as i32)
-                    .unwrap_or(-1)
+            if let Some(session) = sessions.get_mut(&session_id_clone) {
+                let code = session.transport.wait_exit_code();
+                session.alive = false;
+                code
             } else {
                 -1
             }
@@ -123,12 +345,18 @@ pub async fn spawn_pty(
 pub fn write_pty(session_id: String, data: String) -> Result<(), String> {
     let mut sessions = PTY_SESSIONS.lock().unwrap();
     if let Some(session) = sessions.get_mut(&session_id) {
-        session
-            .writer
-            .write_all(data.as_bytes())
-            .map_err(|e| e.to_string())?;
-        session.writer.flush().map_err(|e| e.to_string())?;
-        Ok(())
+        session.transport.write(data.as_bytes())
+    } else {
+        Err("Session not found".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn write_lsp(session_id: String, json: String) -> Result<(), String> {
+    let mut sessions = PTY_SESSIONS.lock().unwrap();
+    if let Some(session) = sessions.get_mut(&session_id) {
+        let frame = format!("Content-Length: {}\r\n\r\n{}", json.as_bytes().len(), json);
+        session.transport.write(frame.as_bytes())
     } else {
         Err("Session not found".to_string())
     }
@@ -138,27 +366,57 @@ pub fn write_pty(session_id: String, data: String) -> Result<(), String> {
 pub fn resize_pty(session_id: String, cols: u16, rows: u16) -> Result<(), String> {
     let sessions = PTY_SESSIONS.lock().unwrap();
     if let Some(session) = sessions.get(&session_id) {
-        session
-            .master
-            .resize(PtySize {
-                rows,
-                cols,
-                pixel_width: 0,
-                pixel_height: 0,
-            })
-            .map_err(|e| e.to_string())?;
+        session.transport.resize(cols, rows)
+    } else {
+        Err("Session not found".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn attach_pty(session_id: String) -> Result<PtyAttachResult, String> {
+    let mut sessions = PTY_SESSIONS.lock().unwrap();
+    if let Some(session) = sessions.get_mut(&session_id) {
+        session.emitting = true;
+        let scrollback = if session.encoding == PtyEncoding::Base64 {
+            BASE64.encode(&session.scrollback)
+        } else {
+            String::from_utf8_lossy(&session.scrollback).to_string()
+        };
+        Ok(PtyAttachResult {
+            scrollback,
+            alive: session.alive,
+        })
+    } else {
+        Err("Session not found".to_string())
+    }
+}
+
+#[tauri::command]
+pub fn detach_pty(session_id: String) -> Result<(), String> {
+    let mut sessions = PTY_SESSIONS.lock().unwrap();
+    if let Some(session) = sessions.get_mut(&session_id) {
+        session.emitting = false;
         Ok(())
     } else {
         Err("Session not found".to_string())
     }
 }
 
+#[tauri::command]
+pub fn signal_pty(session_id: String, signal: String) -> Result<(), String> {
+    let mut sessions = PTY_SESSIONS.lock().unwrap();
+    if let Some(session) = sessions.get_mut(&session_id) {
+        session.transport.signal(&signal)
+    } else {
+        Err("Session not found".to_string())
+    }
+}
+
 #[tauri::command]
 pub fn kill_pty(session_id: String) -> Result<(), String> {
     let mut sessions = PTY_SESSIONS.lock().unwrap();
     if let Some(mut session) = sessions.remove(&session_id) {
-        session.child.kill().map_err(|e| e.to_string())?;
-        Ok(())
+        session.transport.kill()
     } else {
         Err("Session not found".to_string())
     }
@@ -0,0 +1,332 @@
+use portable_pty::{native_pty_system, CommandBuilder, PtySize};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How long a single blocked libssh2 call (read, close, wait-close, ...) may
+/// hold `SshTransport::channel`'s lock before giving up and letting another
+/// caller have a turn. Short enough that `write_pty`/`resize_pty`/`kill_pty`
+/// never stall behind the reader thread's long-poll for remote output.
+const SSH_READ_TIMEOUT_MS: u32 = 100;
+const SSH_LOCK_RETRY_DELAY: Duration = Duration::from_millis(5);
+
+/// True if `err` is libssh2 (or the OS) reporting "nothing happened before
+/// the timeout", as opposed to a real I/O failure worth surfacing.
+fn is_transient_timeout(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::TimedOut | std::io::ErrorKind::WouldBlock
+    )
+}
+
+#[cfg(unix)]
+fn deliver_unix_signal(pid: u32, signal: &str) -> Result<(), String> {
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+
+    let sig = match signal {
+        "SIGINT" => Signal::SIGINT,
+        "SIGTERM" => Signal::SIGTERM,
+        "SIGHUP" => Signal::SIGHUP,
+        "SIGQUIT" => Signal::SIGQUIT,
+        other => return Err(format!("Unsupported signal: {other}")),
+    };
+
+    kill(Pid::from_raw(pid as i32), sig).map_err(|e| e.to_string())
+}
+
+/// Backend a `PtySession` drives its I/O through: a PTY spawned on this
+/// machine, or one forwarded from a remote host over SSH. `spawn_pty` picks
+/// the variant once at session creation; `write_pty`, `resize_pty`,
+/// `kill_pty`, and `signal_pty` dispatch through this trait afterwards
+/// without needing to know which backend they got, mirroring the
+/// local/remote process split used by remote-shell managers.
+pub trait PtyTransport: Send {
+    fn write(&mut self, data: &[u8]) -> Result<(), String>;
+    fn resize(&self, cols: u16, rows: u16) -> Result<(), String>;
+    fn signal(&mut self, signal: &str) -> Result<(), String>;
+    fn kill(&mut self) -> Result<(), String>;
+    fn wait_exit_code(&mut self) -> i32;
+    fn try_clone_reader(&self) -> Result<Box<dyn Read + Send>, String>;
+}
+
+/// Where to run the PTY's child process. Defaults to `Local` when a
+/// `spawn_pty` call omits the field entirely.
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum TransportSpec {
+    Local,
+    Ssh {
+        host: String,
+        #[serde(default = "default_ssh_port")]
+        port: u16,
+        username: String,
+        password: Option<String>,
+        private_key_path: Option<String>,
+    },
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+/// Single-quotes `value` for safe interpolation into the remote shell
+/// command line, escaping any embedded single quotes. Needed because
+/// `Channel::exec` takes one shell command string rather than an argv
+/// array like `CommandBuilder::arg` does for `LocalTransport`.
+fn shell_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+impl Default for TransportSpec {
+    fn default() -> Self {
+        TransportSpec::Local
+    }
+}
+
+pub struct LocalTransport {
+    writer: Box<dyn Write + Send>,
+    child: Box<dyn portable_pty::Child + Send>,
+    master: Box<dyn portable_pty::MasterPty + Send>,
+}
+
+impl LocalTransport {
+    pub fn spawn(
+        cwd: &str,
+        command: String,
+        args: Vec<String>,
+        env: HashMap<String, String>,
+        cols: u16,
+        rows: u16,
+    ) -> Result<Self, String> {
+        let pty_system = native_pty_system();
+
+        let pair = pty_system
+            .openpty(PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| e.to_string())?;
+
+        let mut cmd = CommandBuilder::new(command);
+        cmd.cwd(cwd);
+        for arg in args {
+            cmd.arg(arg);
+        }
+        for (key, value) in env {
+            cmd.env(key, value);
+        }
+
+        let child = pair.slave.spawn_command(cmd).map_err(|e| e.to_string())?;
+        let writer = pair.master.take_writer().map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            writer,
+            child,
+            master: pair.master,
+        })
+    }
+}
+
+impl PtyTransport for LocalTransport {
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        self.writer.write_all(data).map_err(|e| e.to_string())?;
+        self.writer.flush().map_err(|e| e.to_string())
+    }
+
+    fn resize(&self, cols: u16, rows: u16) -> Result<(), String> {
+        self.master
+            .resize(PtySize {
+                rows,
+                cols,
+                pixel_width: 0,
+                pixel_height: 0,
+            })
+            .map_err(|e| e.to_string())
+    }
+
+    fn signal(&mut self, signal: &str) -> Result<(), String> {
+        #[cfg(unix)]
+        {
+            let pid = self
+                .child
+                .process_id()
+                .ok_or_else(|| "Process has no pid".to_string())?;
+            deliver_unix_signal(pid, signal)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = signal;
+            self.kill()
+        }
+    }
+
+    fn kill(&mut self) -> Result<(), String> {
+        self.child.kill().map_err(|e| e.to_string())
+    }
+
+    fn wait_exit_code(&mut self) -> i32 {
+        self.child
+            .wait()
+            .map(|status| status.exit_code() as i32)
+            .unwrap_or(-1)
+    }
+
+    fn try_clone_reader(&self) -> Result<Box<dyn Read + Send>, String> {
+        self.master.try_clone_reader().map_err(|e| e.to_string())
+    }
+}
+
+/// Forwards a PTY running on a remote host over SSH, keeping the same
+/// `pty-data`/`pty-exit` event contract the frontend already expects from
+/// `LocalTransport`. Built on an interactive SSH channel with a PTY
+/// requested on it, so resize maps to `Channel::request_pty_size`. The
+/// channel has no direct signal-delivery primitive, so `signal` falls back
+/// to what a real terminal does: SIGINT is Ctrl-C at the tty layer,
+/// everything else closes the channel.
+pub struct SshTransport {
+    // Kept alive for the lifetime of `channel`, which borrows the underlying
+    // connection; not read after connect.
+    #[allow(dead_code)]
+    session: ssh2::Session,
+    // Shared with the reader handle returned by `try_clone_reader` so the
+    // dedicated reader thread and `write`/`resize`/`kill`/`signal` can both
+    // drive the same channel without racing each other.
+    channel: Arc<Mutex<ssh2::Channel>>,
+}
+
+impl SshTransport {
+    pub fn connect(
+        host: &str,
+        port: u16,
+        username: &str,
+        password: Option<&str>,
+        private_key_path: Option<&str>,
+        cwd: &str,
+        command: String,
+        args: Vec<String>,
+        env: HashMap<String, String>,
+        cols: u16,
+        rows: u16,
+    ) -> Result<Self, String> {
+        let tcp = std::net::TcpStream::connect((host, port)).map_err(|e| e.to_string())?;
+        let mut session = ssh2::Session::new().map_err(|e| e.to_string())?;
+        session.set_tcp_stream(tcp);
+        session.handshake().map_err(|e| e.to_string())?;
+
+        match (password, private_key_path) {
+            (_, Some(key_path)) => session
+                .userauth_pubkey_file(username, None, std::path::Path::new(key_path), None)
+                .map_err(|e| e.to_string())?,
+            (Some(password), None) => session
+                .userauth_password(username, password)
+                .map_err(|e| e.to_string())?,
+            (None, None) => return Err("SSH transport requires a password or private key".into()),
+        }
+
+        let mut channel = session.channel_session().map_err(|e| e.to_string())?;
+        channel
+            .request_pty("xterm-256color", None, Some((cols as u32, rows as u32, 0, 0)))
+            .map_err(|e| e.to_string())?;
+
+        for (key, value) in &env {
+            let _ = channel.setenv(key, value);
+        }
+
+        let full_command = std::iter::once(command)
+            .chain(args)
+            .map(|part| shell_quote(&part))
+            .collect::<Vec<_>>()
+            .join(" ");
+        channel
+            .exec(&format!("cd {} && {full_command}", shell_quote(cwd)))
+            .map_err(|e| e.to_string())?;
+
+        // Bound every blocking libssh2 call so the reader thread never holds
+        // `channel`'s mutex indefinitely waiting on remote data; it instead
+        // times out, drops the lock, and retries. Without this, a
+        // `write`/`resize`/`kill` call racing the reader would deadlock until
+        // the remote host happened to send more output.
+        session.set_timeout(SSH_READ_TIMEOUT_MS);
+
+        Ok(Self {
+            session,
+            channel: Arc::new(Mutex::new(channel)),
+        })
+    }
+}
+
+impl PtyTransport for SshTransport {
+    fn write(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut channel = self.channel.lock().unwrap();
+        channel.write_all(data).map_err(|e| e.to_string())?;
+        channel.flush().map_err(|e| e.to_string())
+    }
+
+    fn resize(&self, cols: u16, rows: u16) -> Result<(), String> {
+        self.channel
+            .lock()
+            .unwrap()
+            .request_pty_size(cols as u32, rows as u32, None, None)
+            .map_err(|e| e.to_string())
+    }
+
+    fn signal(&mut self, signal: &str) -> Result<(), String> {
+        match signal {
+            "SIGINT" => self.write(&[0x03]),
+            _ => self.kill(),
+        }
+    }
+
+    fn kill(&mut self) -> Result<(), String> {
+        self.channel.lock().unwrap().close().map_err(|e| e.to_string())
+    }
+
+    fn wait_exit_code(&mut self) -> i32 {
+        loop {
+            let mut channel = self.channel.lock().unwrap();
+            match channel.wait_close() {
+                Ok(()) => return channel.exit_status().unwrap_or(-1),
+                Err(e) if is_transient_timeout(&std::io::Error::from(e)) => {
+                    drop(channel);
+                    std::thread::sleep(SSH_LOCK_RETRY_DELAY);
+                }
+                Err(_) => return -1,
+            }
+        }
+    }
+
+    fn try_clone_reader(&self) -> Result<Box<dyn Read + Send>, String> {
+        Ok(Box::new(SshChannelReader {
+            channel: Arc::clone(&self.channel),
+        }))
+    }
+}
+
+/// Adapts the shared, mutex-guarded SSH channel to a plain `Read` handle the
+/// reader thread can own independently, the way `LocalTransport` hands out a
+/// cloned `portable_pty` reader.
+struct SshChannelReader {
+    channel: Arc<Mutex<ssh2::Channel>>,
+}
+
+impl Read for SshChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // `channel` blocks for up to `SSH_READ_TIMEOUT_MS` per call (set in
+        // `SshTransport::connect`), so each loop iteration only holds the
+        // lock briefly; a timeout just means "no data yet", not EOF or an
+        // error, so we drop the lock and poll again rather than propagating
+        // it to the reader thread.
+        loop {
+            let result = self.channel.lock().unwrap().read(buf);
+            match result {
+                Err(e) if is_transient_timeout(&e) => std::thread::sleep(SSH_LOCK_RETRY_DELAY),
+                result => return result,
+            }
+        }
+    }
+}